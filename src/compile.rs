@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use crate::instructions::{fresh_register, Condition, Program, Statement};
+
+#[derive(Debug)]
+pub enum CanonicalInstruction {
+    /// `Z(n)`: zero register `n`.
+    Zero { register: String },
+    /// `S(n)`: increment register `n`.
+    Successor { register: String },
+    /// `T(m,n)`: copy register `m` into register `n`.
+    Transfer { from: String, to: String },
+    /// `J(m,n,q)`: jump to instruction `q` if registers `m` and `n` hold
+    /// equal values.
+    Jump {
+        left: String,
+        right: String,
+        target: usize,
+    },
+}
+
+impl CanonicalInstruction {
+    pub fn to_string(&self, instr_number: usize) -> String {
+        match self {
+            CanonicalInstruction::Zero { register } => format!("{}: Z({})", instr_number, register),
+            CanonicalInstruction::Successor { register } => {
+                format!("{}: S({})", instr_number, register)
+            }
+            CanonicalInstruction::Transfer { from, to } => {
+                format!("{}: T({}, {})", instr_number, from, to)
+            }
+            CanonicalInstruction::Jump {
+                left,
+                right,
+                target,
+            } => format!("{}: J({}, {}, {})", instr_number, left, right, target),
+        }
+    }
+}
+
+/// The canonical four-instruction URM program produced by [`compile`]: only
+/// `Z`, `S`, `T` and `J`, addressed the same way as the extended [`Program`]
+/// it was lowered from.
+#[derive(Debug)]
+pub struct CanonicalProgram {
+    pub instructions: Vec<CanonicalInstruction>,
+}
+
+/// A `Jump`'s target before blocks have been laid out end to end: either a
+/// position local to the block being lowered, or the target field of the
+/// original `Goto`/`ConditionalGoto` it came from (resolved against the
+/// whole program once every block's length is known).
+enum JumpTarget {
+    Local(usize),
+    Original(usize),
+}
+
+enum PendingInstruction {
+    Zero(String),
+    Successor(String),
+    Transfer(String, String),
+    Jump(String, String, JumpTarget),
+}
+
+/// Lowers the extended instruction set used elsewhere in this crate down to
+/// the classical four URM primitives. The lowering is purely mechanical and
+/// is only ever applied after subroutine calls have already been inlined by
+/// [`crate::parser::parse_urm_code`], so `program.statements` never contains
+/// a `Statement::Call`.
+pub fn compile(program: &Program) -> CanonicalProgram {
+    let mut used_registers: HashSet<String> = program.input_registers.iter().cloned().collect();
+    used_registers.insert(program.output_register.clone());
+    for statement in &program.statements {
+        let register = match statement {
+            Statement::Increment { register }
+            | Statement::Decrement { register }
+            | Statement::ZeroAssignment { register }
+            | Statement::ConditionalGoto { register, .. } => Some(register),
+            Statement::Goto { .. } | Statement::Call { .. } => None,
+        };
+        if let Some(register) = register {
+            used_registers.insert(register.clone());
+        }
+    }
+
+    // `zero` backs every unconditional jump and every comparison against 0;
+    // `dec_result`/`dec_counter` are shared scratch for the Decrement
+    // expansion below. None can collide with a register the source program
+    // actually uses.
+    let zero = fresh_register("z", &mut used_registers);
+    let dec_result = fresh_register("p", &mut used_registers);
+    let dec_counter = fresh_register("c", &mut used_registers);
+
+    let blocks: Vec<Vec<PendingInstruction>> = program
+        .statements
+        .iter()
+        .map(|statement| lower_statement(statement, &zero, &dec_result, &dec_counter))
+        .collect();
+
+    // Instruction 1 zeroes `zero`; everything else follows, addressed the
+    // same way `program.statements` was. `new_start[i]` is where original
+    // instruction `i`'s block now starts; `new_start[len + 1]` is the
+    // "fall off the end and halt" target.
+    let mut new_start = vec![0usize; program.statements.len() + 2];
+    let mut cursor = 2;
+    for (index, block) in blocks.iter().enumerate() {
+        new_start[index + 1] = cursor;
+        cursor += block.len();
+    }
+    new_start[program.statements.len() + 1] = cursor;
+
+    let mut instructions = vec![CanonicalInstruction::Zero {
+        register: zero.clone(),
+    }];
+    for block in blocks {
+        let base = instructions.len();
+        for pending in block {
+            instructions.push(place(pending, base, &new_start));
+        }
+    }
+
+    CanonicalProgram { instructions }
+}
+
+fn lower_statement(
+    statement: &Statement,
+    zero: &str,
+    dec_result: &str,
+    dec_counter: &str,
+) -> Vec<PendingInstruction> {
+    match statement {
+        Statement::ZeroAssignment { register } => vec![PendingInstruction::Zero(register.clone())],
+        Statement::Increment { register } => vec![PendingInstruction::Successor(register.clone())],
+        Statement::Goto { target } => vec![PendingInstruction::Jump(
+            zero.to_string(),
+            zero.to_string(),
+            JumpTarget::Original(*target),
+        )],
+        Statement::ConditionalGoto {
+            register,
+            condition: Condition::Equal,
+            target,
+        } => vec![PendingInstruction::Jump(
+            register.clone(),
+            zero.to_string(),
+            JumpTarget::Original(*target),
+        )],
+        Statement::ConditionalGoto {
+            register,
+            condition: Condition::NotEqual,
+            target,
+        } => vec![
+            // `register != 0` is the negation of `register == 0`: skip the
+            // unconditional goto below whenever the equality jump would
+            // have fired.
+            PendingInstruction::Jump(register.clone(), zero.to_string(), JumpTarget::Local(3)),
+            PendingInstruction::Jump(
+                zero.to_string(),
+                zero.to_string(),
+                JumpTarget::Original(*target),
+            ),
+        ],
+        Statement::Decrement { register } => vec![
+            // Classic bounded predecessor: count `dec_counter` up from 0
+            // while `dec_result` lags one step behind, until `dec_counter`
+            // reaches `register`'s value. `dec_result` is then `register - 1`
+            // (or 0, if `register` was already 0).
+            PendingInstruction::Zero(dec_result.to_string()),
+            PendingInstruction::Zero(dec_counter.to_string()),
+            PendingInstruction::Jump(
+                dec_counter.to_string(),
+                register.clone(),
+                JumpTarget::Local(7),
+            ),
+            PendingInstruction::Transfer(dec_counter.to_string(), dec_result.to_string()),
+            PendingInstruction::Successor(dec_counter.to_string()),
+            PendingInstruction::Jump(zero.to_string(), zero.to_string(), JumpTarget::Local(3)),
+            PendingInstruction::Transfer(dec_result.to_string(), register.clone()),
+        ],
+        Statement::Call { .. } => {
+            unreachable!("Call statements are inlined away before compilation")
+        }
+    }
+}
+
+fn place(pending: PendingInstruction, base: usize, new_start: &[usize]) -> CanonicalInstruction {
+    match pending {
+        PendingInstruction::Zero(register) => CanonicalInstruction::Zero { register },
+        PendingInstruction::Successor(register) => CanonicalInstruction::Successor { register },
+        PendingInstruction::Transfer(from, to) => CanonicalInstruction::Transfer { from, to },
+        PendingInstruction::Jump(left, right, target) => {
+            let target = match target {
+                JumpTarget::Local(pos) => base + pos,
+                JumpTarget::Original(instr_number) => new_start[instr_number],
+            };
+            CanonicalInstruction::Jump {
+                left,
+                right,
+                target,
+            }
+        }
+    }
+}
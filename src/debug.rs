@@ -5,12 +5,14 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use indexmap::IndexMap;
+use num_bigint::BigUint;
 use ratatui::{
     prelude::*,
     style::{Color, Modifier, Style},
     widgets::{Block, Borders, Paragraph, Wrap},
 };
 use std::{
+    collections::HashSet,
     io,
     time::{Duration, Instant},
 };
@@ -21,30 +23,99 @@ const DEFAULT_TIMEOUT_MILLIS: u64 = 2000;
 pub enum DebugMode {
     Auto { timeout: u64 },
     Manual { step: bool },
+    Continue,
+}
+
+enum StopReason {
+    Halted,
+    Breakpoint,
+    Quit,
 }
 
 pub struct DebuggerState {
     debug_mode: DebugMode,
     instruction_count: usize,
     last_execution: Instant,
+    breakpoints: HashSet<usize>,
+    /// `Some(buffer)` while the `:` command row is open and being typed into.
+    command_input: Option<String>,
+    last_command: Option<String>,
 }
 
 impl DebuggerState {
     fn execute_next_instruction(
         &mut self,
         program: &Program,
-        registers: &mut IndexMap<String, usize>,
+        registers: &mut IndexMap<String, BigUint>,
         pc: &mut usize,
     ) -> bool {
         execute_statement(&program.statements[*pc - 1], registers, pc);
         self.instruction_count += 1;
         *pc > program.statements.len()
     }
+
+    /// Runs the program in a tight loop, without redrawing between steps,
+    /// until a breakpoint is hit, the program halts, or the user quits.
+    fn run_until_stop(
+        &mut self,
+        program: &Program,
+        registers: &mut IndexMap<String, BigUint>,
+        pc: &mut usize,
+    ) -> io::Result<StopReason> {
+        loop {
+            if self.execute_next_instruction(program, registers, pc) {
+                return Ok(StopReason::Halted);
+            }
+
+            if self.breakpoints.contains(pc) {
+                return Ok(StopReason::Breakpoint);
+            }
+
+            if event::poll(Duration::from_millis(0))? {
+                if let Event::Key(key_event) = event::read()? {
+                    if key_event.kind == KeyEventKind::Press {
+                        match key_event.code {
+                            KeyCode::Esc | KeyCode::Char('q') => return Ok(StopReason::Quit),
+                            KeyCode::Char('c')
+                                if key_event.modifiers.contains(event::KeyModifiers::CONTROL) =>
+                            {
+                                return Ok(StopReason::Quit)
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses and applies a command typed into the `:` command row. Unknown
+    /// commands and malformed arguments are silently ignored, mirroring how
+    /// an unrecognised keypress is ignored elsewhere in the debugger.
+    fn run_command(&mut self, command: &str) {
+        let mut parts = command.split_whitespace();
+        match parts.next() {
+            Some("break") => {
+                if let Some(target) = parts.next().and_then(|n| n.parse::<usize>().ok()) {
+                    self.breakpoints.insert(target);
+                }
+            }
+            Some("clear") => {
+                if let Some(target) = parts.next().and_then(|n| n.parse::<usize>().ok()) {
+                    self.breakpoints.remove(&target);
+                }
+            }
+            Some("continue") | Some("run") => {
+                self.debug_mode = DebugMode::Continue;
+            }
+            _ => {}
+        }
+    }
 }
 
 pub fn run_with_debug(
     program: &Program,
-    registers: &mut IndexMap<String, usize>,
+    registers: &mut IndexMap<String, BigUint>,
     pc: &mut usize,
 ) -> io::Result<()> {
     // Terminal setup
@@ -59,6 +130,9 @@ pub fn run_with_debug(
         debug_mode: DebugMode::Manual { step: false },
         instruction_count: 1,
         last_execution: Instant::now(),
+        breakpoints: HashSet::new(),
+        command_input: None,
+        last_command: None,
     };
 
     // Main loop
@@ -84,6 +158,12 @@ pub fn run_with_debug(
                     }
                 }
             }
+            DebugMode::Continue => match debugger_state.run_until_stop(program, registers, pc)? {
+                StopReason::Halted | StopReason::Quit => break,
+                StopReason::Breakpoint => {
+                    debugger_state.debug_mode = DebugMode::Manual { step: false };
+                }
+            },
         }
 
         // Handle input
@@ -103,6 +183,7 @@ fn handle_input(state: &mut DebuggerState) -> io::Result<bool> {
     let timeout_millis = match state.debug_mode {
         DebugMode::Auto { timeout } => timeout,
         DebugMode::Manual { .. } => 100,
+        DebugMode::Continue => 0,
     };
 
     if !event::poll(Duration::from_millis(timeout_millis))? {
@@ -111,6 +192,31 @@ fn handle_input(state: &mut DebuggerState) -> io::Result<bool> {
 
     if let Event::Key(key_event) = event::read()? {
         if key_event.kind == KeyEventKind::Press {
+            if let Some(buffer) = state.command_input.as_mut() {
+                match key_event.code {
+                    KeyCode::Enter => {
+                        // Re-running an empty command repeats the last one.
+                        let command = if buffer.is_empty() {
+                            state.last_command.clone()
+                        } else {
+                            Some(buffer.clone())
+                        };
+                        state.command_input = None;
+                        if let Some(command) = command {
+                            state.run_command(&command);
+                            state.last_command = Some(command);
+                        }
+                    }
+                    KeyCode::Esc => state.command_input = None,
+                    KeyCode::Backspace => {
+                        buffer.pop();
+                    }
+                    KeyCode::Char(c) => buffer.push(c),
+                    _ => {}
+                }
+                return Ok(false);
+            }
+
             match key_event.code {
                 KeyCode::Esc | KeyCode::Char('q') => return Ok(true),
                 KeyCode::Char('c')
@@ -118,6 +224,7 @@ fn handle_input(state: &mut DebuggerState) -> io::Result<bool> {
                 {
                     return Ok(true)
                 }
+                KeyCode::Char(':') => state.command_input = Some(String::new()),
                 code => state.debug_mode.handle_key(code),
             }
         }
@@ -130,7 +237,7 @@ fn ui(
     frame: &mut Frame,
     state: &DebuggerState,
     program: &Program,
-    registers: &IndexMap<String, usize>,
+    registers: &IndexMap<String, BigUint>,
     pc: &usize,
 ) {
     let layout = Layout::new(
@@ -188,15 +295,20 @@ fn ui(
         .map(|(i, statement)| {
             let instr_number = start + i + 1;
             let instr_str = statement.to_string(instr_number);
+            let gutter = if state.breakpoints.contains(&instr_number) {
+                "●"
+            } else {
+                " "
+            };
 
             if instr_number == *pc {
-                Line::from(format!("-> {}", instr_str)).style(
+                Line::from(format!("->{} {}", gutter, instr_str)).style(
                     Style::default()
                         .fg(Color::Green)
                         .add_modifier(Modifier::BOLD),
                 )
             } else {
-                Line::from(format!("   {}", instr_str))
+                Line::from(format!("  {} {}", gutter, instr_str))
             }
         })
         .collect();
@@ -213,13 +325,19 @@ fn ui(
             1000.0 / timeout as f64
         ),
         DebugMode::Manual { .. } => {
-            "Mode: Manual | 'm': Auto | 'Space': Next Instruction".to_string()
+            "Mode: Manual | 'm': Auto | 'Space': Next Instruction | ':': Command".to_string()
         }
+        DebugMode::Continue => "Mode: Continue | running until breakpoint...".to_string(),
     };
 
-    let controls = Paragraph::new(controls_text)
-        .style(Style::default().fg(Color::Yellow))
-        .block(Block::default().borders(Borders::TOP));
+    let mut footer_lines = vec![Line::from(controls_text).style(Style::default().fg(Color::Yellow))];
+    if let Some(buffer) = &state.command_input {
+        footer_lines.push(
+            Line::from(format!(": {}", buffer)).style(Style::default().fg(Color::Magenta)),
+        );
+    }
+
+    let controls = Paragraph::new(footer_lines).block(Block::default().borders(Borders::TOP));
     frame.render_widget(controls, layout[2]);
 }
 
@@ -254,6 +372,7 @@ impl DebugMode {
                 }
                 _ => {}
             },
+            DebugMode::Continue => {}
         }
     }
 }
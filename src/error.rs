@@ -0,0 +1,74 @@
+use std::fmt;
+
+use crate::simulator::Diagnostic;
+
+/// The single error type returned by this crate's library entry points.
+/// `main` is the only place that prints one of these and sets an exit code;
+/// every other function just returns it, so the crate stays embeddable.
+#[derive(Debug)]
+pub enum UrmError {
+    Io(std::io::Error),
+    /// A program couldn't be turned into a `Program`: either pest rejected
+    /// the syntax (in which case `location`/`snippet` pinpoint it), or it
+    /// was syntactically valid but semantically broken (an undefined or
+    /// recursive subroutine call, a missing output register, ...).
+    Parse {
+        message: String,
+        location: Option<(usize, usize)>,
+        snippet: Option<String>,
+    },
+    /// Pre-execution validation found one or more errors; see
+    /// [`crate::simulator::run_static_analysis`].
+    StaticAnalysis(Vec<Diagnostic>),
+    Runtime(String),
+}
+
+impl UrmError {
+    pub(crate) fn parse(message: impl Into<String>) -> Self {
+        UrmError::Parse {
+            message: message.into(),
+            location: None,
+            snippet: None,
+        }
+    }
+}
+
+impl fmt::Display for UrmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrmError::Io(e) => write!(f, "I/O error: {}", e),
+            UrmError::Parse {
+                message,
+                location,
+                snippet,
+            } => {
+                write!(f, "parse error: {}", message)?;
+                if let Some((line, column)) = location {
+                    write!(f, " (line {}, column {})", line, column)?;
+                }
+                if let Some(snippet) = snippet {
+                    write!(f, "\n  {}", snippet)?;
+                }
+                Ok(())
+            }
+            UrmError::StaticAnalysis(diagnostics) => {
+                for (index, diagnostic) in diagnostics.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", diagnostic)?;
+                }
+                Ok(())
+            }
+            UrmError::Runtime(message) => write!(f, "runtime error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for UrmError {}
+
+impl From<std::io::Error> for UrmError {
+    fn from(e: std::io::Error) -> Self {
+        UrmError::Io(e)
+    }
+}
@@ -1,10 +1,12 @@
-#[derive(Debug)]
+use std::collections::HashSet;
+
+#[derive(Debug, Clone)]
 pub enum Condition {
     Equal,
     NotEqual,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Statement {
     ConditionalGoto {
         register: String,
@@ -23,6 +25,13 @@ pub enum Statement {
     Goto {
         target: usize,
     },
+    /// A call to a named subroutine. Only ever present in the statement list
+    /// handed to the flattening pass; `parse_urm_code` inlines every `Call`
+    /// before returning a `Program`, so the rest of the crate never sees one.
+    Call {
+        routine: String,
+        args: Vec<String>,
+    },
 }
 
 impl Statement {
@@ -48,6 +57,9 @@ impl Statement {
                 format!("{}: {} = 0;", instr_number, register)
             }
             Statement::Goto { target } => format!("{}: goto {};", instr_number, target),
+            Statement::Call { routine, args } => {
+                format!("{}: call {}({});", instr_number, routine, args.join(", "))
+            }
         }
     }
 }
@@ -58,3 +70,27 @@ pub struct Program {
     pub statements: Vec<Statement>,
     pub output_register: String,
 }
+
+/// A named, reusable block of statements with its own parameter registers.
+/// Routines are a source-level convenience only: `parse_urm_code` inlines
+/// every call site into a flat, goto-addressed `Program` before execution.
+#[derive(Debug)]
+pub struct Routine {
+    pub name: String,
+    pub params: Vec<String>,
+    pub statements: Vec<Statement>,
+}
+
+/// Synthesizes a register name that can't collide with anything in `used`,
+/// by prefixing `base` with `__` and appending underscores until it's
+/// unique. The chosen name is inserted into `used` before it's returned, so
+/// repeated calls never collide with each other either. Shared by every
+/// pass that invents scratch registers behind the program author's back.
+pub(crate) fn fresh_register(base: &str, used: &mut HashSet<String>) -> String {
+    let mut name = format!("__{}", base);
+    while used.contains(&name) {
+        name.push('_');
+    }
+    used.insert(name.clone());
+    name
+}
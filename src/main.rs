@@ -1,10 +1,16 @@
 use clap::Parser;
+use num_bigint::BigUint;
+use num_traits::Zero;
 
+pub mod compile;
 pub mod debug;
+pub mod error;
 pub mod instructions;
 pub mod parser;
 pub mod simulator;
 
+use error::UrmError;
+
 /// URM code parser and interpreter
 ///
 /// This program reads a URM program from a file and executes it with the given input values.
@@ -16,40 +22,70 @@ struct Args {
     #[arg(index = 1)]
     file: String,
 
-    /// Values for the input registers
+    /// Values for the input registers. Unbounded naturals, so arbitrarily
+    /// large values are accepted.
     #[arg(index = 2)]
-    inputs: Vec<usize>,
+    inputs: Vec<BigUint>,
 
     /// Activate debug mode
     #[arg(short, long)]
     debug: bool,
+
+    /// Abort non-debug execution after this many instructions, to catch
+    /// programs that never halt. Pass 0 to run with no limit.
+    #[arg(long, default_value_t = DEFAULT_MAX_STEPS)]
+    max_steps: u64,
+
+    /// Print the program lowered to the canonical Z/S/T/J URM instruction
+    /// set instead of running it.
+    #[arg(long)]
+    emit_canonical: bool,
 }
 
+const DEFAULT_MAX_STEPS: u64 = 5_000_000;
+
 fn main() {
     let args = Args::parse();
 
-    // Read the URM code from the file
-    let urm_code = match std::fs::read_to_string(&args.file) {
-        Ok(urm_code) => urm_code,
-        Err(e) => {
-            eprintln!("Failed to read input file: {}", e);
-            std::process::exit(1);
-        }
+    let result = if args.emit_canonical {
+        emit_canonical(&args.file)
+    } else {
+        parse_and_execute(&args.file, args.inputs, args.debug, args.max_steps)
     };
 
-    parse_and_execute(urm_code.as_str(), args.inputs, args.debug);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
 
-fn parse_and_execute(urm_code: &str, input: Vec<usize>, debug: bool) {
-    let program = match parser::parse_urm_code(urm_code) {
-        Ok(program) => program,
-        Err(e) => {
-            eprintln!("Failed to parse: {}", e);
-            std::process::exit(1);
-        }
-    };
-
-    let program_result = simulator::simulate_urm(&program, input, debug);
+fn parse_and_execute(
+    file: &str,
+    input: Vec<BigUint>,
+    debug: bool,
+    max_steps: u64,
+) -> Result<(), UrmError> {
+    let urm_code = std::fs::read_to_string(file)?;
+    let program = parser::parse_urm_code(&urm_code)?;
+    let program_result = simulator::simulate_urm(&program, input, debug, max_steps)?;
 
     println!("{}", program_result);
+    Ok(())
+}
+
+fn emit_canonical(file: &str) -> Result<(), UrmError> {
+    let urm_code = std::fs::read_to_string(file)?;
+    let program = parser::parse_urm_code(&urm_code)?;
+
+    // No input values are supplied for a bare lowering, so check everything
+    // that doesn't depend on them; a dummy input of the right length keeps
+    // `check_program` from flagging a length mismatch that's irrelevant here.
+    let dummy_input = vec![BigUint::zero(); program.input_registers.len()];
+    simulator::check_program(&program, &dummy_input)?;
+
+    let canonical = compile::compile(&program);
+    for (index, instruction) in canonical.instructions.iter().enumerate() {
+        println!("{}", instruction.to_string(index + 1));
+    }
+    Ok(())
 }
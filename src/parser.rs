@@ -1,3 +1,6 @@
+use std::collections::{HashMap, HashSet};
+
+use pest::iterators::Pair;
 use pest::Parser;
 use pest_derive::Parser;
 
@@ -5,98 +8,409 @@ use pest_derive::Parser;
 #[grammar = "urm.pest"] // This is the path to the grammar file
 struct URMParser;
 
+use crate::error::UrmError;
 use crate::instructions::*;
 
-pub fn parse_urm_code(input: &str) -> Result<Program, String> {
+pub fn parse_urm_code(input: &str) -> Result<Program, UrmError> {
     // Parse the input using the Pest parser
-    let parsed =
-        URMParser::parse(Rule::program, input).map_err(|e| format!("Parsing error: {}", e))?;
+    let parsed = URMParser::parse(Rule::program, input).map_err(|e| {
+        let (line, column) = match e.line_col {
+            pest::error::LineColLocation::Pos(pos) => pos,
+            pest::error::LineColLocation::Span(start, _) => start,
+        };
+        UrmError::Parse {
+            message: e.variant.to_string(),
+            location: Some((line, column)),
+            snippet: Some(e.line().to_string()),
+        }
+    })?;
 
     // Check if the iterator has only one top-level element (Rule::program)
     let program_pair = parsed
         .into_iter()
         .next()
-        .ok_or_else(|| "Parsing failed: no program rule found".to_string())?;
+        .ok_or_else(|| UrmError::parse("no program rule found"))?;
 
     if program_pair.as_rule() != Rule::program {
-        return Err("Parsing error: expected program rule".to_string());
+        return Err(UrmError::parse("expected program rule"));
     }
 
+    let mut routines = HashMap::new();
     let mut input_registers = Vec::new();
     let mut statements = Vec::new();
     let mut output_register = None;
 
     for pair in program_pair.into_inner() {
         match pair.as_rule() {
+            Rule::routine_decl => {
+                let routine = parse_routine_decl(pair);
+                let name = routine.name.clone();
+                if routines.insert(name.clone(), routine).is_some() {
+                    return Err(UrmError::parse(format!(
+                        "Routine `{}` is defined more than once",
+                        name
+                    )));
+                }
+            }
             Rule::input_decl => {
                 input_registers = pair.into_inner().map(|r| r.as_str().to_string()).collect();
             }
             Rule::statement => {
-                for inner_pair in pair.into_inner() {
-                    match inner_pair.as_rule() {
-                        Rule::increment => {
-                            let register =
-                                inner_pair.into_inner().next().unwrap().as_str().to_string();
-                            statements.push(Statement::Increment { register });
-                        }
-                        Rule::decrement => {
-                            let register =
-                                inner_pair.into_inner().next().unwrap().as_str().to_string();
-                            statements.push(Statement::Decrement { register });
-                        }
-                        Rule::reset => {
-                            let register =
-                                inner_pair.into_inner().next().unwrap().as_str().to_string();
-                            statements.push(Statement::ZeroAssignment { register });
-                        }
-                        Rule::conditional_eq => {
-                            let mut parts = inner_pair.into_inner();
-                            let register = parts.next().unwrap().as_str().to_string();
-                            let target = parts.next().unwrap().as_str().parse::<usize>().unwrap();
-                            statements.push(Statement::ConditionalGoto {
-                                register,
-                                condition: Condition::Equal,
-                                target,
-                            });
-                        }
-                        Rule::conditional_neq => {
-                            let mut parts = inner_pair.into_inner();
-                            let register = parts.next().unwrap().as_str().to_string();
-                            let target = parts.next().unwrap().as_str().parse::<usize>().unwrap();
-                            statements.push(Statement::ConditionalGoto {
-                                register,
-                                condition: Condition::NotEqual,
-                                target,
-                            });
-                        }
-                        Rule::goto => {
-                            let target = inner_pair
-                                .into_inner()
-                                .next()
-                                .unwrap()
-                                .as_str()
-                                .parse::<usize>()
-                                .unwrap();
-                            statements.push(Statement::Goto { target });
-                        }
-                        _ => unreachable!("Unexpected statement rule: {:?}", inner_pair),
-                    }
-                }
+                statements.push(parse_statement(pair));
             }
             Rule::output_decl => {
                 output_register = Some(pair.into_inner().next().unwrap().as_str().to_string());
             }
+            Rule::EOI => {}
             _ => unreachable!("Unexpected top level rule: {:?}", pair),
         }
     }
 
-    if let Some(output) = output_register {
-        Ok(Program {
-            input_registers,
-            statements,
-            output_register: output,
-        })
-    } else {
-        Err("No output register found".to_string())
+    let output_register =
+        output_register.ok_or_else(|| UrmError::parse("no output register found"))?;
+
+    let mut used =
+        collect_used_registers(&input_registers, &output_register, &statements, &routines);
+    let mut cache = HashMap::new();
+    let mut visiting = HashSet::new();
+    let statements =
+        flatten_statements(&statements, &routines, &mut cache, &mut visiting, &mut used)?;
+
+    Ok(Program {
+        input_registers,
+        statements,
+        output_register,
+    })
+}
+
+/// Collects every register name the program already mentions: inputs, the
+/// output, every register touched by a statement (including a `Call`'s
+/// arguments), and every routine's parameters. Seeds the `used` set that
+/// keeps call-site scratch registers (see `expand_call`) from aliasing one
+/// of them.
+fn collect_used_registers(
+    input_registers: &[String],
+    output_register: &str,
+    statements: &[Statement],
+    routines: &HashMap<String, Routine>,
+) -> HashSet<String> {
+    let mut used: HashSet<String> = input_registers.iter().cloned().collect();
+    used.insert(output_register.to_string());
+
+    fn visit(statements: &[Statement], used: &mut HashSet<String>) {
+        for statement in statements {
+            match statement {
+                Statement::Increment { register }
+                | Statement::Decrement { register }
+                | Statement::ZeroAssignment { register }
+                | Statement::ConditionalGoto { register, .. } => {
+                    used.insert(register.clone());
+                }
+                Statement::Call { args, .. } => used.extend(args.iter().cloned()),
+                Statement::Goto { .. } => {}
+            }
+        }
+    }
+
+    visit(statements, &mut used);
+    for routine in routines.values() {
+        used.extend(routine.params.iter().cloned());
+        visit(&routine.statements, &mut used);
+    }
+
+    used
+}
+
+fn parse_routine_decl(pair: Pair<Rule>) -> Routine {
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+
+    let mut params = Vec::new();
+    let mut statements = Vec::new();
+    for item in inner {
+        match item.as_rule() {
+            Rule::identifier => params.push(item.as_str().to_string()),
+            Rule::statement => statements.push(parse_statement(item)),
+            _ => unreachable!("Unexpected routine_decl rule: {:?}", item),
+        }
+    }
+
+    Routine {
+        name,
+        params,
+        statements,
+    }
+}
+
+fn parse_statement(pair: Pair<Rule>) -> Statement {
+    let inner_pair = pair.into_inner().next().unwrap();
+    match inner_pair.as_rule() {
+        Rule::increment => {
+            let register = inner_pair.into_inner().next().unwrap().as_str().to_string();
+            Statement::Increment { register }
+        }
+        Rule::decrement => {
+            let register = inner_pair.into_inner().next().unwrap().as_str().to_string();
+            Statement::Decrement { register }
+        }
+        Rule::reset => {
+            let register = inner_pair.into_inner().next().unwrap().as_str().to_string();
+            Statement::ZeroAssignment { register }
+        }
+        Rule::conditional_eq => {
+            let mut parts = inner_pair.into_inner();
+            let register = parts.next().unwrap().as_str().to_string();
+            let target = parts.next().unwrap().as_str().parse::<usize>().unwrap();
+            Statement::ConditionalGoto {
+                register,
+                condition: Condition::Equal,
+                target,
+            }
+        }
+        Rule::conditional_neq => {
+            let mut parts = inner_pair.into_inner();
+            let register = parts.next().unwrap().as_str().to_string();
+            let target = parts.next().unwrap().as_str().parse::<usize>().unwrap();
+            Statement::ConditionalGoto {
+                register,
+                condition: Condition::NotEqual,
+                target,
+            }
+        }
+        Rule::goto => {
+            let target = inner_pair
+                .into_inner()
+                .next()
+                .unwrap()
+                .as_str()
+                .parse::<usize>()
+                .unwrap();
+            Statement::Goto { target }
+        }
+        Rule::call => {
+            let mut parts = inner_pair.into_inner();
+            let routine = parts.next().unwrap().as_str().to_string();
+            let args = parts.map(|arg| arg.as_str().to_string()).collect();
+            Statement::Call { routine, args }
+        }
+        _ => unreachable!("Unexpected statement rule: {:?}", inner_pair),
+    }
+}
+
+/// Inlines every `Call` in `statements` against `routines`, rewriting goto
+/// targets so the result is a plain, goto-addressed statement list. Flattened
+/// routine bodies are memoized in `cache`; `visiting` detects call cycles,
+/// which URM's flat register machine has no call stack to support.
+fn flatten_statements(
+    statements: &[Statement],
+    routines: &HashMap<String, Routine>,
+    cache: &mut HashMap<String, Vec<Statement>>,
+    visiting: &mut HashSet<String>,
+    used: &mut HashSet<String>,
+) -> Result<Vec<Statement>, UrmError> {
+    // Expand each source statement into its final shape. An inlined call's
+    // copy-in + callee body (1..=N) has targets relative to its own start;
+    // a plain statement keeps its original targets, remapped below.
+    let mut chunks = Vec::with_capacity(statements.len());
+    for statement in statements {
+        match statement {
+            Statement::Call { routine, args } => chunks.push(expand_call(
+                routine, args, routines, cache, visiting, used,
+            )?),
+            other => chunks.push(vec![other.clone()]),
+        }
+    }
+
+    // Map each original instruction number (including the one-past-the-end
+    // "fall off and halt" target) to where its expansion now starts.
+    let mut new_start = vec![0usize; statements.len() + 2];
+    let mut cursor = 1;
+    for (index, chunk) in chunks.iter().enumerate() {
+        new_start[index + 1] = cursor;
+        cursor += chunk.len();
+    }
+    new_start[statements.len() + 1] = cursor;
+
+    let mut flattened = Vec::with_capacity(cursor - 1);
+    for (index, statement) in statements.iter().enumerate() {
+        match statement {
+            Statement::Call { .. } => {
+                let base = new_start[index + 1];
+                for inlined in &chunks[index] {
+                    flattened.push(shift_target(inlined.clone(), base - 1));
+                }
+            }
+            plain => flattened.push(remap_target(plain, &new_start)?),
+        }
     }
+
+    Ok(flattened)
+}
+
+/// Flattens `name`'s body (memoized) and returns the statements that inline
+/// one call to it: copy-in instructions for each non-aliased argument,
+/// followed by the callee's body shifted to start right after them.
+fn expand_call(
+    name: &str,
+    args: &[String],
+    routines: &HashMap<String, Routine>,
+    cache: &mut HashMap<String, Vec<Statement>>,
+    visiting: &mut HashSet<String>,
+    used: &mut HashSet<String>,
+) -> Result<Vec<Statement>, UrmError> {
+    let routine = routines
+        .get(name)
+        .ok_or_else(|| UrmError::parse(format!("Call to undefined routine `{}`", name)))?;
+
+    if args.len() != routine.params.len() {
+        return Err(UrmError::parse(format!(
+            "Routine `{}` expects {} argument(s), but {} were given",
+            name,
+            routine.params.len(),
+            args.len()
+        )));
+    }
+
+    let mut expanded = Vec::new();
+    for (index, (arg, param)) in args.iter().zip(&routine.params).enumerate() {
+        if arg != param {
+            // `fresh_register` (shared with `compile.rs`) keeps this from
+            // aliasing a register the source program already uses under
+            // the same name this scheme would otherwise pick.
+            let scratch = fresh_register(&format!("{}_copy{}", name, index), used);
+            // `copy_instructions`' targets are local to its own block (1-11);
+            // shift them by however much has already been placed in
+            // `expanded`, the same way the callee body is shifted below.
+            let block_offset = expanded.len();
+            expanded.extend(
+                copy_instructions(arg, param, &scratch)
+                    .into_iter()
+                    .map(|s| shift_target(s, block_offset)),
+            );
+        }
+    }
+
+    let body = flatten_routine(name, routines, cache, visiting, used)?;
+    let offset = expanded.len();
+    expanded.extend(body.into_iter().map(|s| shift_target(s, offset)));
+
+    Ok(expanded)
+}
+
+fn flatten_routine(
+    name: &str,
+    routines: &HashMap<String, Routine>,
+    cache: &mut HashMap<String, Vec<Statement>>,
+    visiting: &mut HashSet<String>,
+    used: &mut HashSet<String>,
+) -> Result<Vec<Statement>, UrmError> {
+    if let Some(flattened) = cache.get(name) {
+        return Ok(flattened.clone());
+    }
+
+    if !visiting.insert(name.to_string()) {
+        return Err(UrmError::parse(format!(
+            "Routine `{}` is part of a recursive call cycle, which URM has no call stack to support",
+            name
+        )));
+    }
+
+    // Unwrap is safe: `expand_call` already checked `name` exists before
+    // calling here.
+    let routine = routines.get(name).unwrap();
+    let flattened = flatten_statements(&routine.statements, routines, cache, visiting, used)?;
+
+    visiting.remove(name);
+    cache.insert(name.to_string(), flattened.clone());
+
+    Ok(flattened)
+}
+
+/// A non-destructive copy of `from` into `to`, using `scratch` to hold
+/// `from`'s value while it is rebuilt. All three registers must be distinct.
+fn copy_instructions(from: &str, to: &str, scratch: &str) -> Vec<Statement> {
+    vec![
+        Statement::ZeroAssignment {
+            register: to.to_string(),
+        },
+        Statement::ZeroAssignment {
+            register: scratch.to_string(),
+        },
+        Statement::ConditionalGoto {
+            register: from.to_string(),
+            condition: Condition::Equal,
+            target: 7,
+        },
+        Statement::Decrement {
+            register: from.to_string(),
+        },
+        Statement::Increment {
+            register: scratch.to_string(),
+        },
+        Statement::Goto { target: 3 },
+        Statement::ConditionalGoto {
+            register: scratch.to_string(),
+            condition: Condition::Equal,
+            target: 12,
+        },
+        Statement::Decrement {
+            register: scratch.to_string(),
+        },
+        Statement::Increment {
+            register: from.to_string(),
+        },
+        Statement::Increment {
+            register: to.to_string(),
+        },
+        Statement::Goto { target: 7 },
+    ]
+}
+
+fn shift_target(statement: Statement, offset: usize) -> Statement {
+    match statement {
+        Statement::Goto { target } => Statement::Goto {
+            target: target + offset,
+        },
+        Statement::ConditionalGoto {
+            register,
+            condition,
+            target,
+        } => Statement::ConditionalGoto {
+            register,
+            condition,
+            target: target + offset,
+        },
+        other => other,
+    }
+}
+
+/// Looks up `target` in `new_start`, which is only populated for the
+/// original instruction numbers 1..=len+1; the grammar accepts goto targets
+/// of any magnitude, so this is the first point one can be rejected.
+fn resolve_target(target: usize, new_start: &[usize]) -> Result<usize, UrmError> {
+    new_start.get(target).copied().ok_or_else(|| {
+        UrmError::parse(format!(
+            "goto target {} is out of range (program has {} instructions)",
+            target,
+            new_start.len() - 2
+        ))
+    })
+}
+
+fn remap_target(statement: &Statement, new_start: &[usize]) -> Result<Statement, UrmError> {
+    Ok(match statement {
+        Statement::Goto { target } => Statement::Goto {
+            target: resolve_target(*target, new_start)?,
+        },
+        Statement::ConditionalGoto {
+            register,
+            condition,
+            target,
+        } => Statement::ConditionalGoto {
+            register: register.clone(),
+            condition: condition.clone(),
+            target: resolve_target(*target, new_start)?,
+        },
+        other => other.clone(),
+    })
 }
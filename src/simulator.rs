@@ -1,23 +1,52 @@
-use std::collections::HashSet;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
 
 use indexmap::IndexMap;
+use num_bigint::BigUint;
+use num_traits::Zero;
 
 use crate::debug::run_with_debug;
+use crate::error::UrmError;
 use crate::instructions::*;
 
-pub fn simulate_urm(program: &Program, input: Vec<usize>, debug: bool) -> usize {
-    // Run static analysis
-    match run_static_analysis(program, &input) {
-        Ok(_) => {}
-        Err(e) => {
-            // This should never happen, as the parser should catch these errors
-            eprintln!("Static analysis failed: {}", e);
-            std::process::exit(1);
-        }
+/// How many of the most recently executed instruction numbers to report
+/// when the step budget in `run_without_debug` is exhausted.
+const STEP_HISTORY_LEN: usize = 5;
+
+/// Runs [`run_static_analysis`] and reports its findings: warnings are
+/// printed immediately, since they don't stop anything; errors are
+/// collected into an [`UrmError::StaticAnalysis`] and returned instead, so
+/// the caller decides whether to abort. Every front-end that needs a
+/// validated `Program` before doing something with it should go through
+/// this rather than calling `run_static_analysis` directly.
+pub fn check_program(program: &Program, input: &[BigUint]) -> Result<(), UrmError> {
+    let diagnostics = run_static_analysis(program, input);
+    for diagnostic in diagnostics.iter().filter(|d| d.severity == Severity::Warning) {
+        eprintln!("{}", diagnostic);
     }
+    let errors: Vec<Diagnostic> = diagnostics
+        .into_iter()
+        .filter(|d| d.severity == Severity::Error)
+        .collect();
+    if !errors.is_empty() {
+        return Err(UrmError::StaticAnalysis(errors));
+    }
+    Ok(())
+}
+
+/// Runs `program` to completion and returns the value left in its output
+/// register. See [`check_program`] for how validation findings are
+/// reported.
+pub fn simulate_urm(
+    program: &Program,
+    input: Vec<BigUint>,
+    debug: bool,
+    max_steps: u64,
+) -> Result<BigUint, UrmError> {
+    check_program(program, &input)?;
 
     // Initialize registers
-    let mut registers: IndexMap<String, usize> =
+    let mut registers: IndexMap<String, BigUint> =
         program.input_registers.iter().cloned().zip(input).collect();
 
     // Initialize program counter
@@ -25,56 +54,75 @@ pub fn simulate_urm(program: &Program, input: Vec<usize>, debug: bool) -> usize
 
     // Run the program
     match debug {
-        true => run_with_debug(program, &mut registers, &mut pc).unwrap(),
-        false => run_without_debug(program, &mut registers, &mut pc),
+        true => run_with_debug(program, &mut registers, &mut pc)?,
+        false => run_without_debug(program, &mut registers, &mut pc, max_steps)?,
     };
 
     // Output the result
     let output_register = &program.output_register;
-    let output_value = registers.get(output_register).unwrap_or(&0);
-
-    *output_value
+    Ok(registers.get(output_register).cloned().unwrap_or_else(BigUint::zero))
 }
 
+/// Runs `program` to completion. `max_steps` bounds how many instructions
+/// may execute before this aborts with an error instead of hanging forever;
+/// pass 0 to run with no limit.
 pub fn run_without_debug(
     program: &Program,
-    registers: &mut IndexMap<String, usize>,
+    registers: &mut IndexMap<String, BigUint>,
     pc: &mut usize,
-) {
-    // Run the program
+    max_steps: u64,
+) -> Result<(), UrmError> {
+    let mut steps: u64 = 0;
+    let mut history: VecDeque<usize> = VecDeque::with_capacity(STEP_HISTORY_LEN);
+
     loop {
+        history.push_back(*pc);
+        if history.len() > STEP_HISTORY_LEN {
+            history.pop_front();
+        }
+
         execute_statement(&program.statements[*pc - 1], registers, pc);
+        steps += 1;
 
         // Check if the program has terminated
         if *pc > program.statements.len() {
-            break;
+            return Ok(());
+        }
+
+        if max_steps != 0 && steps >= max_steps {
+            let last_instructions = history
+                .iter()
+                .map(|instr_number| instr_number.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(UrmError::Runtime(format!(
+                "execution exceeded {} steps, possible non-termination (stopped at instruction {}; last executed instructions: {})",
+                max_steps, pc, last_instructions
+            )));
         }
     }
 }
 
 pub fn execute_statement(
     statement: &Statement,
-    registers: &mut IndexMap<String, usize>,
+    registers: &mut IndexMap<String, BigUint>,
     pc: &mut usize,
 ) {
     // Execute the statement
     match statement {
         Statement::Increment { register } => {
-            let value = registers.get(register).unwrap_or(&0) + 1;
-            registers.insert(register.clone(), value);
+            *registers.entry(register.clone()).or_insert_with(BigUint::zero) += 1u8;
             *pc += 1;
         }
         Statement::Decrement { register } => {
-            let value = registers
-                .get(register)
-                .unwrap_or(&0)
-                .checked_sub(1)
-                .unwrap_or(0);
-            registers.insert(register.clone(), value);
+            let value = registers.entry(register.clone()).or_insert_with(BigUint::zero);
+            if *value > BigUint::zero() {
+                *value -= 1u8;
+            }
             *pc += 1;
         }
         Statement::ZeroAssignment { register } => {
-            registers.insert(register.clone(), 0);
+            registers.insert(register.clone(), BigUint::zero());
             *pc += 1;
         }
         Statement::ConditionalGoto {
@@ -82,18 +130,19 @@ pub fn execute_statement(
             condition,
             target,
         } => {
-            let value = registers.get(register).unwrap_or(&0);
+            let zero = BigUint::zero();
+            let value = registers.get(register).unwrap_or(&zero);
             let target_pc = *target;
             let new_pc = match condition {
                 Condition::Equal => {
-                    if value == &0 {
+                    if value == &zero {
                         target_pc
                     } else {
                         *pc + 1
                     }
                 }
                 Condition::NotEqual => {
-                    if value != &0 {
+                    if value != &zero {
                         target_pc
                     } else {
                         *pc + 1
@@ -105,20 +154,164 @@ pub fn execute_statement(
         Statement::Goto { target } => {
             *pc = *target;
         }
+        Statement::Call { .. } => {
+            unreachable!("Call statements are inlined away before a Program is executed")
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A single static-analysis finding. `instruction` is the 1-based statement
+/// number it concerns, or `None` for findings about the program as a whole.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub instruction: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn error(instruction: Option<usize>, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            instruction,
+            message,
+        }
+    }
+
+    fn warning(instruction: Option<usize>, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            instruction,
+            message,
+        }
     }
 }
 
-pub fn run_static_analysis(program: &Program, input: &Vec<usize>) -> Result<(), String> {
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.instruction {
+            Some(instr_number) => write!(f, "{} at instruction {}: {}", label, instr_number, self.message),
+            None => write!(f, "{}: {}", label, self.message),
+        }
+    }
+}
+
+/// Validates `program` before it is run, returning every finding instead of
+/// stopping at the first one so a front-end can display them all at once.
+/// Warnings and errors are returned together, so a non-empty result does
+/// not by itself mean the program is invalid; check each `Diagnostic`'s
+/// severity.
+pub fn run_static_analysis(program: &Program, input: &[BigUint]) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
     // Check if input registers are unique
     if program.input_registers.len() != program.input_registers.iter().collect::<HashSet<_>>().len()
     {
-        return Err("Input registers must be unique".to_string());
+        diagnostics.push(Diagnostic::error(
+            None,
+            "Input registers must be unique".to_string(),
+        ));
     }
 
     // Check if length of input registers matches the length of the input vector
     if program.input_registers.len() != input.len() {
-        return Err(format!("Input vector length does not match input register length. Program expects {} inputs, but {} were provided", program.input_registers.len(), input.len()));
+        diagnostics.push(Diagnostic::error(None, format!(
+            "Input vector length does not match input register length. Program expects {} inputs, but {} were provided",
+            program.input_registers.len(), input.len()
+        )));
     }
 
-    Ok(())
+    let len = program.statements.len();
+    for (index, statement) in program.statements.iter().enumerate() {
+        let target = match statement {
+            Statement::Goto { target } => Some(*target),
+            Statement::ConditionalGoto { target, .. } => Some(*target),
+            _ => None,
+        };
+
+        if let Some(target) = target {
+            // `len + 1` is the legitimate "fall off the end and halt" target.
+            if target == 0 || target > len + 1 {
+                diagnostics.push(Diagnostic::error(
+                    Some(index + 1),
+                    format!(
+                        "goto target {} is out of range (program has {} instructions)",
+                        target, len
+                    ),
+                ));
+            }
+        }
+    }
+
+    for instr_number in unreachable_instructions(program) {
+        diagnostics.push(Diagnostic::warning(
+            Some(instr_number),
+            "instruction is unreachable".to_string(),
+        ));
+    }
+
+    if len > 0 && !output_register_is_assigned(program) {
+        diagnostics.push(Diagnostic::warning(
+            None,
+            format!(
+                "output register `{}` is never assigned",
+                program.output_register
+            ),
+        ));
+    }
+
+    diagnostics
+}
+
+/// A forward/backward reachability scan from instruction 1 over the
+/// control-flow graph implied by `Goto`/`ConditionalGoto` targets and
+/// fall-through. Targets out of range are ignored here; they are reported
+/// separately.
+fn unreachable_instructions(program: &Program) -> Vec<usize> {
+    let len = program.statements.len();
+    if len == 0 {
+        return Vec::new();
+    }
+
+    let mut reachable = vec![false; len + 1]; // 1-indexed; index 0 unused
+    let mut to_visit = vec![1usize];
+    while let Some(instr_number) = to_visit.pop() {
+        if instr_number == 0 || instr_number > len || reachable[instr_number] {
+            continue;
+        }
+        reachable[instr_number] = true;
+
+        match &program.statements[instr_number - 1] {
+            Statement::Goto { target } => to_visit.push(*target),
+            Statement::ConditionalGoto { target, .. } => {
+                to_visit.push(*target);
+                to_visit.push(instr_number + 1);
+            }
+            _ => to_visit.push(instr_number + 1),
+        }
+    }
+
+    (1..=len).filter(|instr_number| !reachable[*instr_number]).collect()
+}
+
+fn output_register_is_assigned(program: &Program) -> bool {
+    program.statements.iter().any(|statement| {
+        matches!(
+            statement,
+            Statement::Increment { register }
+            | Statement::Decrement { register }
+            | Statement::ZeroAssignment { register }
+            if register == &program.output_register
+        )
+    })
 }